@@ -0,0 +1,286 @@
+// macOS support: the agent ships as a .pkg under packages.wazuh.com/<channel>/macos/ and picks
+// up its manager and enrollment settings from /tmp/wazuh_envs during installation
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::{
+    agent_health, check_output, check_privileges, check_timeout, emit_event, find_version_field,
+    install_file, loggable_command, package_tempfile, package_url_override, path_arg, run_as_root,
+    run_as_root_checked, run_as_root_with_timeout, verified_package, wait_for_agent, AgentHealth,
+    CommandRunner, Config, InstallError, InstallOptions, InstallStatus, Installer,
+    PackageTransaction,
+};
+
+const AGENT_DIR: &str = "/Library/Ossec";
+const WAZUH_CONTROL: &str = "/Library/Ossec/bin/wazuh-control";
+const AGENTD_STATE: &str = "/Library/Ossec/var/run/wazuh-agentd.state";
+const LAUNCH_DAEMON: &str = "/Library/LaunchDaemons/com.wazuh.agent.plist";
+const PACKAGE_ID: &str = "com.wazuh.pkg.wazuh-agent";
+// Read by the package's postinstall script
+const INSTALL_ENVS: &str = "/tmp/wazuh_envs";
+
+pub(crate) struct MacOsInstaller;
+
+impl MacOsInstaller {
+    fn architecture(&self, runner: &dyn CommandRunner) -> Result<&'static str, InstallError> {
+        let output = runner.run("uname", &["-m"]).map_err(|_| {
+            InstallError::ArchitectureDetectionError("Failed to run uname.".to_string())
+        })?;
+
+        match String::from_utf8_lossy(&output.stdout).trim() {
+            "arm64" => Ok("arm64"),
+            "x86_64" => Ok("x86_64"),
+            other => Err(InstallError::ArchitectureDetectionError(format!(
+                "Unsupported architecture '{}' (from uname -m). The macOS agent is published \
+                 for arm64 and x86_64",
+                other
+            ))),
+        }
+    }
+
+    fn is_installed(&self, _runner: &dyn CommandRunner) -> Result<bool, InstallError> {
+        Ok(Path::new(WAZUH_CONTROL).exists())
+    }
+
+    fn installed_version(
+        &self,
+        runner: &dyn CommandRunner,
+    ) -> Result<Option<String>, InstallError> {
+        if !Path::new(WAZUH_CONTROL).exists() {
+            return Ok(None);
+        }
+
+        let output = runner.run(WAZUH_CONTROL, &["info"])?;
+        if !output.status.success() {
+            return Err(InstallError::InstallationError(
+                "Failed to query the installed Wazuh agent version.".to_string(),
+            ));
+        }
+
+        Ok(find_version_field(
+            &String::from_utf8_lossy(&output.stdout),
+            "WAZUH_VERSION=",
+        ))
+    }
+}
+
+impl Installer for MacOsInstaller {
+    fn platform(&self, runner: &dyn CommandRunner) -> Result<(String, String), InstallError> {
+        Ok(("macos".to_string(), self.architecture(runner)?.to_string()))
+    }
+
+    fn status(
+        &self,
+        runner: &dyn CommandRunner,
+        options: &InstallOptions,
+    ) -> Result<InstallStatus, InstallError> {
+        Ok(InstallStatus::new(
+            self.is_installed(runner)?,
+            self.installed_version(runner)?,
+            options.wazuh_version(),
+        ))
+    }
+
+    fn package_url(
+        &self,
+        runner: &dyn CommandRunner,
+        options: &InstallOptions,
+    ) -> Result<String, InstallError> {
+        if let Some(url) = package_url_override(options, "pkg")? {
+            return Ok(url);
+        }
+        // Intel packages are tagged intel64 rather than x86_64
+        let package_arch = match self.architecture(runner)? {
+            "arm64" => "arm64",
+            _ => "intel64",
+        };
+        Ok(format!(
+            "{}/macos/wazuh-agent-{}-1.{}.pkg",
+            options.package_base(),
+            options.wazuh_version(),
+            package_arch
+        ))
+    }
+
+    fn install(
+        &self,
+        runner: &dyn CommandRunner,
+        options: &InstallOptions,
+        config: Option<&Config>,
+    ) -> Result<(), InstallError> {
+        let package_url = self.package_url(runner, options)?;
+
+        let mut envs = Vec::new();
+        if let Some(config) = config {
+            envs.push(format!("WAZUH_MANAGER='{}'", config.manager_address));
+            if let Some(agent_name) = &config.agent_name {
+                envs.push(format!("WAZUH_AGENT_NAME='{}'", agent_name));
+            }
+            if let Some(group) = &config.agent_group {
+                envs.push(format!("WAZUH_AGENT_GROUP='{}'", group));
+            }
+            if let Some(port) = config.manager_port {
+                envs.push(format!("WAZUH_MANAGER_PORT='{}'", port));
+            }
+            if let Some(protocol) = &config.manager_protocol {
+                envs.push(format!("WAZUH_PROTOCOL='{}'", protocol));
+            }
+            if let Some(port) = config.enrollment_port {
+                envs.push(format!("WAZUH_REGISTRATION_PORT='{}'", port));
+            }
+            if let Some(password) = &config.registration_password {
+                envs.push(format!("WAZUH_REGISTRATION_PASSWORD='{}'", password));
+            }
+        }
+
+        if options.dry_run {
+            options.say(&format!("Package URL: {}", package_url));
+            if !envs.is_empty() {
+                options.say(&format!("Would write manager settings to {}", INSTALL_ENVS));
+            }
+            options.say(&format!(
+                "Install command: installer -pkg wazuh-agent-XXXXXX.pkg -target / (agent under {})",
+                AGENT_DIR
+            ));
+            return Ok(());
+        }
+
+        let mut package_file = package_tempfile(options, ".pkg")?;
+        let package_path = verified_package(runner, options, &package_url, &mut package_file)?;
+        let package_str = path_arg(&package_path)?;
+
+        check_privileges(runner, options)?;
+
+        if !envs.is_empty() {
+            install_file(runner, options, &(envs.join("\n") + "\n"), INSTALL_ENVS)?;
+        }
+
+        let install_args = ["installer", "-pkg", package_str, "-target", "/"];
+        let command = loggable_command(&install_args);
+        emit_event("install_start", serde_json::json!({ "command": command }));
+        let result = {
+            let _transaction = PackageTransaction::begin();
+            run_as_root_with_timeout(runner, options, &install_args, options.timeout())
+        };
+        check_timeout(&result, "Package install")?;
+        check_output(result, &install_args, InstallError::InstallationError)?;
+        emit_event("install_done", serde_json::json!({ "command": command }));
+        Ok(())
+    }
+
+    fn install_command(
+        &self,
+        _runner: &dyn CommandRunner,
+        _options: &InstallOptions,
+        package_path: &str,
+    ) -> Result<String, InstallError> {
+        Ok(format!("installer -pkg {} -target /", package_path))
+    }
+
+    fn service_commands(
+        &self,
+        _runner: &dyn CommandRunner,
+        _options: &InstallOptions,
+    ) -> Result<Vec<String>, InstallError> {
+        Ok(vec![format!("{} start", WAZUH_CONTROL)])
+    }
+
+    fn configure(
+        &self,
+        _runner: &dyn CommandRunner,
+        _options: &InstallOptions,
+        _config: &Config,
+    ) -> Result<Option<PathBuf>, InstallError> {
+        // The manager settings were already handed to the package through INSTALL_ENVS
+        Ok(None)
+    }
+
+    fn restore_config(
+        &self,
+        _runner: &dyn CommandRunner,
+        _options: &InstallOptions,
+        _backup: &Path,
+    ) -> Result<(), InstallError> {
+        // configure() never makes a backup here
+        Ok(())
+    }
+
+    fn register(
+        &self,
+        _runner: &dyn CommandRunner,
+        _options: &InstallOptions,
+        _config: &Config,
+    ) -> Result<bool, InstallError> {
+        // The agent enrolls itself on first start from the settings passed in INSTALL_ENVS
+        Ok(false)
+    }
+
+    fn start_service(
+        &self,
+        runner: &dyn CommandRunner,
+        options: &InstallOptions,
+    ) -> Result<String, InstallError> {
+        run_as_root_checked(
+            runner,
+            options,
+            &[WAZUH_CONTROL, "start"],
+            InstallError::ServiceError,
+        )?;
+        wait_for_agent(self, runner, options)?;
+
+        // status prints one "<daemon> is running..." line per agent daemon
+        let status = run_as_root(runner, options, &[WAZUH_CONTROL, "status"])?;
+        Ok(String::from_utf8_lossy(&status.stdout)
+            .lines()
+            .next()
+            .map(|line| line.trim().to_string())
+            .unwrap_or_else(|| "unknown".to_string()))
+    }
+
+    fn health(
+        &self,
+        runner: &dyn CommandRunner,
+        options: &InstallOptions,
+    ) -> Result<AgentHealth, InstallError> {
+        agent_health(runner, options, WAZUH_CONTROL, AGENTD_STATE)
+    }
+
+    fn stop_service(
+        &self,
+        runner: &dyn CommandRunner,
+        options: &InstallOptions,
+    ) -> Result<(), InstallError> {
+        run_as_root_checked(
+            runner,
+            options,
+            &[WAZUH_CONTROL, "stop"],
+            InstallError::ServiceError,
+        )?;
+
+        Ok(())
+    }
+
+    fn uninstall(
+        &self,
+        runner: &dyn CommandRunner,
+        options: &InstallOptions,
+    ) -> Result<(), InstallError> {
+        check_privileges(runner, options)?;
+
+        // Stop the agent before removing it; an agent that isn't running is not an error
+        let _ = run_as_root(runner, options, &[WAZUH_CONTROL, "stop"]);
+        let _ = run_as_root(runner, options, &["launchctl", "unload", LAUNCH_DAEMON]);
+
+        info!("Removing {}", AGENT_DIR);
+        for args in [
+            &["rm", "-rf", AGENT_DIR][..],
+            &["rm", "-f", LAUNCH_DAEMON],
+            &["pkgutil", "--forget", PACKAGE_ID],
+        ] {
+            run_as_root_checked(runner, options, args, InstallError::InstallationError)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+// self-check: compares this binary with the newest wazuhchecker release on GitHub and, with
+// --self-update, swaps it for that release's build for this OS and architecture
+use std::cmp::Ordering;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use log::info;
+use reqwest::header::{ACCEPT, USER_AGENT};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build_http_client, check_checksum, compare_versions, download_with_retry,
+    fetch_expected_checksum, CommandRunner, InstallError, InstallOptions, RETRY_BASE_DELAY,
+};
+
+const LATEST_RELEASE_API: &str = "https://api.github.com/repos/mranv/wazuhchecker/releases/latest";
+const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    html_url: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+// What self-check found. download_url is the build for this host when the release has
+// one, else the release page
+#[derive(Serialize)]
+pub(crate) struct SelfCheck {
+    pub(crate) current: &'static str,
+    pub(crate) latest: String,
+    pub(crate) update_available: bool,
+    pub(crate) download_url: String,
+    pub(crate) updated: bool,
+}
+
+pub(crate) fn self_check(
+    runner: &dyn CommandRunner,
+    options: &InstallOptions,
+    update: bool,
+    expected_sha256: Option<&str>,
+) -> Result<SelfCheck, InstallError> {
+    let release = latest_release(options)?;
+    let latest = release.tag_name.trim_start_matches('v').to_string();
+    let update_available = compare_versions(CURRENT_VERSION, &latest) == Ordering::Less;
+    let build = release
+        .assets
+        .iter()
+        .find(|asset| is_host_build(&asset.name));
+
+    let mut check = SelfCheck {
+        current: CURRENT_VERSION,
+        latest,
+        update_available,
+        download_url: build.map_or_else(
+            || release.html_url.clone(),
+            |asset| asset.browser_download_url.clone(),
+        ),
+        updated: false,
+    };
+    if !update || !update_available {
+        return Ok(check);
+    }
+
+    let build = build.ok_or_else(|| {
+        InstallError::DownloadError(format!(
+            "Release {} has no build for {}-{}, see {}",
+            release.tag_name,
+            env::consts::OS,
+            env::consts::ARCH,
+            release.html_url
+        ))
+    })?;
+    if options.dry_run {
+        options.say(&format!("Would replace this binary with {}", build.name));
+        return Ok(check);
+    }
+
+    // A binary nobody can vouch for doesn't replace this one. --sha256 is the agent
+    // package's digest and never this binary's
+    let checksum_name = format!("{}.sha256", build.name);
+    let expected_sha256 = match (
+        expected_sha256,
+        release.assets.iter().find(|a| a.name == checksum_name),
+    ) {
+        (Some(digest), _) => digest.to_string(),
+        (None, Some(checksum)) => {
+            fetch_expected_checksum(runner, &checksum.browser_download_url, options)?
+        }
+        (None, None) => {
+            return Err(InstallError::ChecksumError(format!(
+                "Release {} publishes no {}, pass --self-update-sha256 to update anyway",
+                release.tag_name, checksum_name
+            )))
+        }
+    };
+    replace_current_exe(options, &build.browser_download_url, &expected_sha256)?;
+    check.updated = true;
+
+    Ok(check)
+}
+
+fn latest_release(options: &InstallOptions) -> Result<Release, InstallError> {
+    // GitHub's API rejects requests without a User-Agent
+    let response = build_http_client(options)?
+        .get(LATEST_RELEASE_API)
+        .header(USER_AGENT, format!("wazuhchecker/{}", CURRENT_VERSION))
+        .header(ACCEPT, "application/vnd.github+json")
+        .send()
+        .map_err(|e| {
+            InstallError::DownloadError(format!(
+                "Could not reach GitHub to look for a newer wazuhchecker: {}",
+                e
+            ))
+        })?;
+    if !response.status().is_success() {
+        return Err(InstallError::DownloadError(format!(
+            "Server returned HTTP {} for {}",
+            response.status(),
+            LATEST_RELEASE_API
+        )));
+    }
+
+    let body = response
+        .text()
+        .map_err(|e| InstallError::DownloadError(format!("Failed to read response body: {}", e)))?;
+    serde_json::from_str(&body).map_err(|e| {
+        InstallError::DownloadError(format!(
+            "Unexpected response from {}: {}",
+            LATEST_RELEASE_API, e
+        ))
+    })
+}
+
+// Release builds are named after the OS and architecture they're for, with their checksums
+// alongside as <name>.sha256
+fn is_host_build(name: &str) -> bool {
+    name.contains(env::consts::OS) && name.contains(env::consts::ARCH) && !name.ends_with(".sha256")
+}
+
+// Downloads next to the running binary and renames over it, so the swap is atomic and an
+// interrupted update leaves the old binary in place
+fn replace_current_exe(
+    options: &InstallOptions,
+    url: &str,
+    expected_sha256: &str,
+) -> Result<(), InstallError> {
+    let exe = env::current_exe()?;
+    let dir = exe.parent().unwrap_or(Path::new("."));
+    let staged = tempfile::Builder::new()
+        .prefix(".wazuhchecker-")
+        .tempfile_in(dir)?;
+
+    let actual_sha256 = download_with_retry(
+        &build_http_client(options)?,
+        url,
+        staged.path(),
+        options.download_attempts(),
+        RETRY_BASE_DELAY,
+        options.show_progress(),
+    )?;
+    check_checksum(staged.path(), &actual_sha256, expected_sha256)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(staged.path(), fs::Permissions::from_mode(0o755))?;
+    }
+    // Windows won't replace a running executable, but it will rename it out of the way
+    #[cfg(windows)]
+    fs::rename(&exe, exe.with_extension("exe.old"))?;
+
+    staged
+        .persist(&exe)
+        .map_err(|e| InstallError::IOError(e.error))?;
+    info!("Replaced {}", exe.display());
+
+    Ok(())
+}
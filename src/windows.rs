@@ -0,0 +1,310 @@
+// Windows support: the agent ships as an MSI under packages.wazuh.com/<channel>/windows/ and
+// takes its manager and enrollment settings as msiexec properties
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::{
+    check_output, check_timeout, emit_event, loggable_command, package_tempfile,
+    package_url_override, parse_agentd_state, path_arg, run_checked, verified_package,
+    wait_for_agent, AgentHealth, CommandRunner, Config, DaemonStatus, InstallError, InstallOptions,
+    InstallStatus, Installer, PackageTransaction,
+};
+
+const AGENT_DIR: &str = r"C:\Program Files (x86)\ossec-agent";
+const UNINSTALL_KEY: &str = r"HKLM\SOFTWARE\WOW6432Node\Microsoft\Windows\CurrentVersion\Uninstall";
+const SERVICE_NAME: &str = "WazuhSvc";
+
+pub(crate) struct WindowsInstaller;
+
+impl WindowsInstaller {
+    // sc prints a "STATE : 4  RUNNING" line among others
+    fn service_state(&self, runner: &dyn CommandRunner) -> Result<String, InstallError> {
+        let query = runner.run("sc", &["query", SERVICE_NAME])?;
+        Ok(String::from_utf8_lossy(&query.stdout)
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("STATE"))
+            .map(|state| state.trim_start_matches([' ', ':']).to_string())
+            .unwrap_or_else(|| "unknown".to_string()))
+    }
+
+    // Uninstall registry key of the "Wazuh Agent" product, whose last segment is the MSI product code
+    fn product_key(&self, runner: &dyn CommandRunner) -> Result<Option<String>, InstallError> {
+        let output = runner.run(
+            "reg",
+            &["query", UNINSTALL_KEY, "/s", "/f", "Wazuh Agent", "/d"],
+        )?;
+        // reg exits with 1 when nothing matches
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .find(|line| line.starts_with("HKEY_"))
+            .map(String::from))
+    }
+
+    fn is_installed(&self, runner: &dyn CommandRunner) -> Result<bool, InstallError> {
+        if Path::new(AGENT_DIR).join("wazuh-agent.exe").exists() {
+            return Ok(true);
+        }
+        Ok(self.product_key(runner)?.is_some())
+    }
+
+    fn installed_version(
+        &self,
+        runner: &dyn CommandRunner,
+    ) -> Result<Option<String>, InstallError> {
+        let key = match self.product_key(runner)? {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+
+        // Matching line looks like "    DisplayVersion    REG_SZ    4.7.3"
+        let output = runner.run("reg", &["query", &key, "/v", "DisplayVersion"])?;
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .find_map(|line| {
+                let mut fields = line.split_whitespace();
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some("DisplayVersion"), Some(_), Some(version)) => Some(version.to_string()),
+                    _ => None,
+                }
+            }))
+    }
+}
+
+impl Installer for WindowsInstaller {
+    fn platform(&self, _runner: &dyn CommandRunner) -> Result<(String, String), InstallError> {
+        let architecture = env::var("PROCESSOR_ARCHITECTURE")
+            .map(|arch| arch.to_lowercase())
+            .map_err(|_| {
+                InstallError::ArchitectureDetectionError(
+                    "PROCESSOR_ARCHITECTURE is not set".to_string(),
+                )
+            })?;
+        Ok(("windows".to_string(), architecture))
+    }
+
+    fn status(
+        &self,
+        runner: &dyn CommandRunner,
+        options: &InstallOptions,
+    ) -> Result<InstallStatus, InstallError> {
+        Ok(InstallStatus::new(
+            self.is_installed(runner)?,
+            self.installed_version(runner)?,
+            options.wazuh_version(),
+        ))
+    }
+
+    fn package_url(
+        &self,
+        _runner: &dyn CommandRunner,
+        options: &InstallOptions,
+    ) -> Result<String, InstallError> {
+        if let Some(url) = package_url_override(options, "msi")? {
+            return Ok(url);
+        }
+        Ok(format!(
+            "{}/windows/wazuh-agent-{}-1.msi",
+            options.package_base(),
+            options.wazuh_version()
+        ))
+    }
+
+    fn install(
+        &self,
+        runner: &dyn CommandRunner,
+        options: &InstallOptions,
+        config: Option<&Config>,
+    ) -> Result<(), InstallError> {
+        let package_url = self.package_url(runner, options)?;
+
+        let mut properties = Vec::new();
+        if let Some(config) = config {
+            properties.push(format!("WAZUH_MANAGER={}", config.manager_address));
+            if let Some(agent_name) = &config.agent_name {
+                properties.push(format!("WAZUH_AGENT_NAME={}", agent_name));
+            }
+            if let Some(group) = &config.agent_group {
+                properties.push(format!("WAZUH_AGENT_GROUP={}", group));
+            }
+            if let Some(port) = config.manager_port {
+                properties.push(format!("WAZUH_MANAGER_PORT={}", port));
+            }
+            if let Some(protocol) = &config.manager_protocol {
+                properties.push(format!("WAZUH_PROTOCOL={}", protocol));
+            }
+            if let Some(port) = config.enrollment_port {
+                properties.push(format!("WAZUH_REGISTRATION_PORT={}", port));
+            }
+            if let Some(password) = &config.registration_password {
+                properties.push(format!("WAZUH_REGISTRATION_PASSWORD={}", password));
+            }
+        }
+
+        if options.dry_run {
+            options.say(&format!("Package URL: {}", package_url));
+            options.say(&format!(
+                "Install command: msiexec /i wazuh-agent-{}-1.msi /q{}",
+                options.wazuh_version(),
+                if properties.is_empty() {
+                    ""
+                } else {
+                    " <manager properties>"
+                }
+            ));
+            return Ok(());
+        }
+
+        let mut package_file = package_tempfile(options, ".msi")?;
+        let package_path = verified_package(runner, options, &package_url, &mut package_file)?;
+        let package_str = path_arg(&package_path)?;
+
+        let mut args = vec!["/i", package_str, "/q"];
+        args.extend(properties.iter().map(String::as_str));
+
+        info!("Running msiexec /i {} /q", package_str);
+        let mut command = vec!["msiexec"];
+        command.extend(&args);
+        let logged = loggable_command(&command);
+        emit_event("install_start", serde_json::json!({ "command": logged }));
+        let result = {
+            let _transaction = PackageTransaction::begin();
+            runner.run_with_timeout("msiexec", &args, options.timeout())
+        };
+        check_timeout(&result, "Package install")?;
+        check_output(result, &command, InstallError::InstallationError)?;
+        emit_event("install_done", serde_json::json!({ "command": logged }));
+        Ok(())
+    }
+
+    fn install_command(
+        &self,
+        _runner: &dyn CommandRunner,
+        _options: &InstallOptions,
+        package_path: &str,
+    ) -> Result<String, InstallError> {
+        // The manager properties come from --config at install time, not from the plan
+        Ok(format!("msiexec /i {} /q", package_path))
+    }
+
+    fn service_commands(
+        &self,
+        _runner: &dyn CommandRunner,
+        _options: &InstallOptions,
+    ) -> Result<Vec<String>, InstallError> {
+        Ok(vec![format!("net start {}", SERVICE_NAME)])
+    }
+
+    fn configure(
+        &self,
+        _runner: &dyn CommandRunner,
+        _options: &InstallOptions,
+        _config: &Config,
+    ) -> Result<Option<PathBuf>, InstallError> {
+        // The manager settings were already handed to msiexec as properties
+        Ok(None)
+    }
+
+    fn restore_config(
+        &self,
+        _runner: &dyn CommandRunner,
+        _options: &InstallOptions,
+        _backup: &Path,
+    ) -> Result<(), InstallError> {
+        // configure() never makes a backup here
+        Ok(())
+    }
+
+    fn register(
+        &self,
+        _runner: &dyn CommandRunner,
+        _options: &InstallOptions,
+        _config: &Config,
+    ) -> Result<bool, InstallError> {
+        // The agent enrolls itself on first start from the settings passed in msiexec properties
+        Ok(false)
+    }
+
+    fn start_service(
+        &self,
+        runner: &dyn CommandRunner,
+        options: &InstallOptions,
+    ) -> Result<String, InstallError> {
+        run_checked(
+            runner,
+            "net",
+            &["start", SERVICE_NAME],
+            InstallError::ServiceError,
+        )?;
+
+        wait_for_agent(self, runner, options)?;
+        self.service_state(runner)
+    }
+
+    fn health(
+        &self,
+        runner: &dyn CommandRunner,
+        _options: &InstallOptions,
+    ) -> Result<AgentHealth, InstallError> {
+        // One service hosts every agent component on Windows
+        let running = self.service_state(runner)?.ends_with("RUNNING");
+        let connection = fs::read_to_string(Path::new(AGENT_DIR).join("wazuh-agent.state"))
+            .ok()
+            .and_then(|content| parse_agentd_state(&content));
+
+        Ok(AgentHealth {
+            daemons: vec![DaemonStatus {
+                name: SERVICE_NAME.to_string(),
+                running,
+            }],
+            connection,
+        })
+    }
+
+    fn stop_service(
+        &self,
+        runner: &dyn CommandRunner,
+        _options: &InstallOptions,
+    ) -> Result<(), InstallError> {
+        run_checked(
+            runner,
+            "net",
+            &["stop", SERVICE_NAME],
+            InstallError::ServiceError,
+        )?;
+
+        Ok(())
+    }
+
+    fn uninstall(
+        &self,
+        runner: &dyn CommandRunner,
+        _options: &InstallOptions,
+    ) -> Result<(), InstallError> {
+        let key = self.product_key(runner)?.ok_or_else(|| {
+            InstallError::InstallationError("Wazuh agent is not installed.".to_string())
+        })?;
+        let product_code = key.rsplit('\\').next().unwrap_or(&key);
+
+        // Stop the agent before removing it; a service that isn't running is not an error
+        let _ = runner.run("net", &["stop", SERVICE_NAME]);
+
+        info!("Running msiexec /x {} /q", product_code);
+        let _transaction = PackageTransaction::begin();
+        run_checked(
+            runner,
+            "msiexec",
+            &["/x", product_code, "/q"],
+            InstallError::InstallationError,
+        )?;
+
+        Ok(())
+    }
+}
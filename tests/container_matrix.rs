@@ -0,0 +1,130 @@
+//! Opt-in check of distribution detection: runs a dry-run install in each image of the
+//! matrix and expects that distribution's package URL in the output. Nothing is installed
+//! and packages.wazuh.com isn't contacted.
+//!
+//! Needs docker (or CONTAINER_RUNTIME=podman) and the x86_64-unknown-linux-musl target,
+//! since the one static binary has to run on glibc and musl images alike.
+//!
+//!   cargo test --test container_matrix -- --ignored
+//!   CONTAINER_MATRIX_FILTER=rocky cargo test --test container_matrix -- --ignored
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const TARGET: &str = "x86_64-unknown-linux-musl";
+const BASE: &str = "https://packages.wazuh.com/4.x";
+
+// image, os-release fixture from tests/fixtures/os-release (None for the image's own),
+// expected URL relative to BASE
+const MATRIX: &[(&str, Option<&str>, &str)] = &[
+    (
+        "ubuntu:22.04",
+        None,
+        "apt/pool/main/w/wazuh-agent/wazuh-agent_4.7.3-1_amd64.deb",
+    ),
+    (
+        "debian:12",
+        None,
+        "apt/pool/main/w/wazuh-agent/wazuh-agent_4.7.3-1_amd64.deb",
+    ),
+    ("centos:7", None, "yum/wazuh-agent-4.7.3-1.x86_64.rpm"),
+    ("fedora:39", None, "yum/wazuh-agent-4.7.3-1.x86_64.rpm"),
+    (
+        "alpine:3.19",
+        None,
+        "alpine/v3.12/main/x86_64/wazuh-agent-4.7.3-r1.apk",
+    ),
+    (
+        "debian:12",
+        Some("linuxmint-21"),
+        "apt/pool/main/w/wazuh-agent/wazuh-agent_4.7.3-1_amd64.deb",
+    ),
+    (
+        "debian:12",
+        Some("opensuse-tumbleweed"),
+        "yum/wazuh-agent-4.7.3-1.x86_64.rpm",
+    ),
+    (
+        "centos:7",
+        Some("rocky-9"),
+        "yum/wazuh-agent-4.7.3-1.x86_64.rpm",
+    ),
+    (
+        "centos:7",
+        Some("centos-5"),
+        "yum5/x86_64/wazuh-agent-4.7.3-1.el5.x86_64.rpm",
+    ),
+];
+
+fn build_static_binary(root: &Path) -> PathBuf {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let status = Command::new(cargo)
+        .current_dir(root)
+        .args(["build", "--release", "--target", TARGET])
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "cargo build --target {TARGET} failed");
+    root.join("target")
+        .join(TARGET)
+        .join("release/wazuhchecher")
+}
+
+#[test]
+#[ignore = "needs docker or podman and the x86_64-unknown-linux-musl target"]
+fn dry_run_picks_each_distributions_package() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let runtime = env::var("CONTAINER_RUNTIME").unwrap_or_else(|_| "docker".to_string());
+    let filter = env::var("CONTAINER_MATRIX_FILTER").unwrap_or_default();
+    let binary = build_static_binary(root);
+
+    let mut failures = Vec::new();
+    for &(image, fixture, expected) in MATRIX {
+        let label = format!("{image} ({})", fixture.unwrap_or("-"));
+        if !label.contains(&filter) {
+            continue;
+        }
+
+        let mut command = Command::new(&runtime);
+        command.args(["run", "--rm"]).arg(format!(
+            "--volume={}:/usr/local/bin/wazuhchecker:ro",
+            binary.display()
+        ));
+        // Mounts the fixture over the image's /etc/os-release, so one image can pose as
+        // another distribution from the same family
+        if let Some(fixture) = fixture {
+            let path = root.join("tests/fixtures/os-release").join(fixture);
+            command.arg(format!("--volume={}:/etc/os-release:ro", path.display()));
+        }
+        command.args([
+            image,
+            "wazuhchecker",
+            "install",
+            "--dry-run",
+            "--assume-yes",
+            "--skip-connectivity-check",
+            "--skip-space-check",
+        ]);
+
+        let output = command
+            .output()
+            .unwrap_or_else(|e| panic!("failed to run {runtime}: {e}"));
+        let text = format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let expected = format!("Package URL: {BASE}/{expected}");
+        if output.status.success() && text.contains(&expected) {
+            println!("ok    {label}");
+        } else {
+            println!("FAIL  {label}: expected {expected}");
+            for line in text.lines() {
+                println!("      {line}");
+            }
+            failures.push(label);
+        }
+    }
+
+    assert!(failures.is_empty(), "failed entries: {failures:?}");
+}
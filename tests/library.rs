@@ -0,0 +1,39 @@
+//! The checker used as a dependency, through its public API only
+
+use std::fs;
+
+use wazuhchecher::{install, load_config, InstallError, InstallOptions};
+
+#[test]
+fn a_malformed_version_is_refused_before_anything_is_touched() {
+    let options = InstallOptions::default().version("4.7").dry_run(true);
+    let error = install(&options, None).unwrap_err();
+    assert!(matches!(error, InstallError::ArgumentError(_)), "{}", error);
+    assert!(error.to_string().contains("4.7"), "{}", error);
+}
+
+#[test]
+fn configs_load_from_a_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("wazuhchecker.toml");
+    fs::write(
+        &path,
+        "manager_address = \"wazuh.example.com\"\nagent_group = \"web\"\n",
+    )
+    .unwrap();
+    load_config(&path).unwrap();
+
+    fs::write(
+        &path,
+        "manager_address = \"wazuh.example.com\"\nmanager_port = 0\n",
+    )
+    .unwrap();
+    assert!(matches!(
+        load_config(&path),
+        Err(InstallError::ConfigError(_))
+    ));
+    assert!(matches!(
+        load_config(&dir.path().join("missing.toml")),
+        Err(InstallError::ConfigError(_))
+    ));
+}
@@ -0,0 +1,41 @@
+//! --quiet as a script sees it: nothing on stdout, errors still on stderr, exit codes kept
+#![cfg(target_os = "linux")]
+
+use std::fs;
+use std::process::{Command, Output};
+
+fn dry_run(package: &std::path::Path, extra: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_wazuhchecher"))
+        .args([
+            "install",
+            "--dry-run",
+            "--assume-yes",
+            "--no-sudo",
+            "--offline",
+        ])
+        .arg(package)
+        .args(["--skip-connectivity-check", "--skip-space-check"])
+        .args(extra)
+        .output()
+        .expect("failed to run wazuhchecker")
+}
+
+#[test]
+fn a_quiet_run_prints_nothing_but_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let package = dir.path().join("wazuh-agent_4.7.3-1_amd64.deb");
+    fs::write(&package, b"!<arch>\ndebian-binary").unwrap();
+
+    let chatty = dry_run(&package, &[]);
+    assert!(chatty.status.success(), "{:?}", chatty);
+    assert!(String::from_utf8_lossy(&chatty.stdout).contains("Dry run complete"));
+
+    let quiet = dry_run(&package, &["--quiet"]);
+    assert!(quiet.status.success(), "{:?}", quiet);
+    assert_eq!(String::from_utf8_lossy(&quiet.stdout), "");
+
+    let failed = dry_run(&package, &["--quiet", "--version", "4.7"]);
+    assert_eq!(failed.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&failed.stdout), "");
+    assert!(String::from_utf8_lossy(&failed.stderr).contains("4.7"));
+}